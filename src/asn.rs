@@ -0,0 +1,100 @@
+use std::fs::File;
+use std::io::{self, BufRead};
+use std::net::Ipv4Addr;
+
+// AsnEntry is one row of the offline prefix -> ASN table.
+struct AsnEntry {
+    network: u32,
+    prefix_len: u8,
+    asn: u32,
+    name: String,
+}
+
+// AsnDb maps candidate IPs to the origin ASN (and AS name) of their longest
+// matching announced prefix, so the final ranked list can be spread across
+// distinct networks instead of being ten IPs from the same datacenter. The
+// matched prefix itself is not returned to callers.
+pub struct AsnDb {
+    entries: Vec<AsnEntry>,
+}
+
+impl AsnDb {
+    // Loads a CSV of `prefix,asn[,name]` rows, e.g. `1.1.1.0/24,13335,CLOUDFLARENET`.
+    pub fn load(path: &str) -> io::Result<AsnDb> {
+        let file = File::open(path)?;
+        let mut entries = Vec::new();
+
+        for line in io::BufReader::new(file).lines() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split(',').collect();
+            if fields.len() < 2 {
+                continue;
+            }
+
+            let (network, prefix_len) = match parse_cidr(fields[0]) {
+                Some(v) => v,
+                None => continue,
+            };
+            let asn: u32 = match fields[1].parse() {
+                Ok(a) => a,
+                Err(_) => continue,
+            };
+            let name = fields.get(2).map(|s| s.trim().to_string()).unwrap_or_default();
+
+            entries.push(AsnEntry {
+                network,
+                prefix_len,
+                asn,
+                name,
+            });
+        }
+
+        Ok(AsnDb { entries })
+    }
+
+    // lookup returns the (ASN, name) of the longest matching prefix for
+    // `ip`, or None if no loaded prefix covers it.
+    //
+    // IPv4-only: the table and matching below only understand dotted-quad
+    // prefixes, so every IPv6 candidate always returns None here. Callers
+    // that diversify on the result (e.g. --max-per-asn) must account for
+    // that rather than assuming None means "no match yet checked".
+    pub fn lookup(&self, ip: &str) -> Option<(u32, String)> {
+        let addr: Ipv4Addr = ip.parse().ok()?;
+        let ip_bits = u32::from(addr);
+
+        let mut best: Option<&AsnEntry> = None;
+        for entry in &self.entries {
+            if prefix_matches(ip_bits, entry.network, entry.prefix_len)
+                && best.is_none_or(|b| entry.prefix_len > b.prefix_len)
+            {
+                best = Some(entry);
+            }
+        }
+
+        best.map(|e| (e.asn, e.name.clone()))
+    }
+}
+
+fn parse_cidr(s: &str) -> Option<(u32, u8)> {
+    let mut parts = s.splitn(2, '/');
+    let addr: Ipv4Addr = parts.next()?.parse().ok()?;
+    let len: u8 = parts.next()?.parse().ok()?;
+    if len > 32 {
+        return None;
+    }
+    Some((u32::from(addr), len))
+}
+
+fn prefix_matches(ip: u32, network: u32, prefix_len: u8) -> bool {
+    if prefix_len == 0 {
+        return true;
+    }
+    let mask = (!0u32) << (32 - prefix_len as u32);
+    (ip & mask) == (network & mask)
+}
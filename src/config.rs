@@ -0,0 +1,126 @@
+use std::fs;
+use std::io::{self, Write};
+
+use serde::{Deserialize, Serialize};
+
+// OutputConfig controls how the ranked results are written once a scan
+// finishes: "text" (the classic per-line format), "json", or "base64" (a
+// v2ray-style subscription blob of newline-joined links).
+#[derive(Deserialize, Serialize, Clone)]
+pub struct OutputConfig {
+    #[serde(default = "default_format")]
+    pub format: String,
+}
+
+impl Default for OutputConfig {
+    fn default() -> Self {
+        OutputConfig {
+            format: default_format(),
+        }
+    }
+}
+
+fn default_format() -> String {
+    "text".to_string()
+}
+
+// OptimizerConfig is the on-disk, reusable replacement for positional CLI
+// args. `node_links` takes more than one entry so a single scan produces
+// an optimized set for every node in a subscription.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct OptimizerConfig {
+    pub node_links: Vec<String>,
+    pub ip_list_file: String,
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+    #[serde(default = "default_workers")]
+    pub workers: usize,
+    #[serde(default = "default_top_n")]
+    pub top_n: usize,
+    #[serde(default)]
+    pub output: OutputConfig,
+}
+
+fn default_timeout_ms() -> u64 {
+    3000
+}
+
+fn default_workers() -> usize {
+    4
+}
+
+fn default_top_n() -> usize {
+    10
+}
+
+impl OptimizerConfig {
+    // load reads a YAML or TOML config, picked by file extension.
+    pub fn load(path: &str) -> io::Result<OptimizerConfig> {
+        let data = fs::read_to_string(path)?;
+        if path.ends_with(".toml") {
+            toml::from_str(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        } else {
+            serde_yaml::from_str(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        }
+    }
+
+    // save writes the config back out in the same format implied by its
+    // extension, so `--wizard` output can be reused directly with `--config`.
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        let data = if path.ends_with(".toml") {
+            toml::to_string_pretty(self).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+        } else {
+            serde_yaml::to_string(self).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+        };
+        fs::write(path, data)
+    }
+}
+
+fn prompt(label: &str, default: &str) -> String {
+    if default.is_empty() {
+        print!("{}: ", label);
+    } else {
+        print!("{} [{}]: ", label, default);
+    }
+    io::stdout().flush().unwrap();
+
+    let mut line = String::new();
+    io::stdin().read_line(&mut line).unwrap();
+    let trimmed = line.trim();
+    if trimmed.is_empty() {
+        default.to_string()
+    } else {
+        trimmed.to_string()
+    }
+}
+
+// run_wizard interactively builds a config, following vpncloud's
+// config-wizard approach, so people who don't want to memorize the
+// positional argument order can still get a working setup.
+pub fn run_wizard() -> OptimizerConfig {
+    println!("cdn_optimizer setup wizard (leave a node link blank to stop adding)");
+
+    let mut node_links = Vec::new();
+    loop {
+        let link = prompt(&format!("Node link #{}", node_links.len() + 1), "");
+        if link.is_empty() {
+            break;
+        }
+        node_links.push(link);
+    }
+
+    let ip_list_file = prompt("IP list file", "ips.txt");
+    let timeout_ms: u64 = prompt("Timeout (ms)", "3000").parse().unwrap_or(3000);
+    let workers: usize = prompt("Concurrent workers", "4").parse().unwrap_or(4);
+    let top_n: usize = prompt("Top-N results per node", "10").parse().unwrap_or(10);
+    let format = prompt("Output format (text/json/base64)", "text");
+
+    OptimizerConfig {
+        node_links,
+        ip_list_file,
+        timeout_ms,
+        workers,
+        top_n,
+        output: OutputConfig { format },
+    }
+}
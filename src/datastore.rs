@@ -0,0 +1,193 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufWriter, Write};
+
+// AddressState tracks what we currently believe about an IP+port pair,
+// so repeat runs can skip known-bad nodes instead of re-probing them
+// from scratch every time.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AddressState {
+    Untested,
+    Good,
+    WasGood,
+    Timeout,
+    Refused,
+    EvilNode,
+}
+
+impl AddressState {
+    fn as_str(&self) -> &'static str {
+        match self {
+            AddressState::Untested => "Untested",
+            AddressState::Good => "Good",
+            AddressState::WasGood => "WasGood",
+            AddressState::Timeout => "Timeout",
+            AddressState::Refused => "Refused",
+            AddressState::EvilNode => "EvilNode",
+        }
+    }
+
+    fn from_str(s: &str) -> Option<AddressState> {
+        match s {
+            "Untested" => Some(AddressState::Untested),
+            "Good" => Some(AddressState::Good),
+            "WasGood" => Some(AddressState::WasGood),
+            "Timeout" => Some(AddressState::Timeout),
+            "Refused" => Some(AddressState::Refused),
+            "EvilNode" => Some(AddressState::EvilNode),
+            _ => None,
+        }
+    }
+}
+
+// AddressEntry is the persisted record for a single IP+port pair.
+#[derive(Clone)]
+pub struct AddressEntry {
+    pub state: AddressState,
+    pub avg_latency_ms: f64,
+    pub last_seen_unix: u64,
+    pub success_count: u32,
+    pub fail_count: u32,
+}
+
+impl AddressEntry {
+    fn new() -> AddressEntry {
+        AddressEntry {
+            state: AddressState::Untested,
+            avg_latency_ms: 0.0,
+            last_seen_unix: 0,
+            success_count: 0,
+            fail_count: 0,
+        }
+    }
+}
+
+// DataStore is the in-memory view of the on-disk result history, keyed by
+// (ip, port) so the same IP tested against different node ports is tracked
+// separately.
+pub struct DataStore {
+    path: String,
+    pub entries: HashMap<(String, u16), AddressEntry>,
+}
+
+impl DataStore {
+    // Loads the store from `path`, or starts empty if it doesn't exist yet.
+    pub fn load(path: &str) -> DataStore {
+        let mut entries = HashMap::new();
+
+        if let Ok(file) = File::open(path) {
+            for line in io::BufReader::new(file).lines() {
+                let line = match line {
+                    Ok(l) => l,
+                    Err(_) => continue,
+                };
+                let fields: Vec<&str> = line.trim().split(',').collect();
+                if fields.len() != 7 {
+                    continue;
+                }
+                let ip = fields[0].to_string();
+                let port: u16 = match fields[1].parse() {
+                    Ok(p) => p,
+                    Err(_) => continue,
+                };
+                let state = match AddressState::from_str(fields[2]) {
+                    Some(s) => s,
+                    None => continue,
+                };
+                let avg_latency_ms: f64 = fields[3].parse().unwrap_or(0.0);
+                let last_seen_unix: u64 = fields[4].parse().unwrap_or(0);
+                let success_count: u32 = fields[5].parse().unwrap_or(0);
+                let fail_count: u32 = fields[6].parse().unwrap_or(0);
+
+                entries.insert(
+                    (ip, port),
+                    AddressEntry {
+                        state,
+                        avg_latency_ms,
+                        last_seen_unix,
+                        success_count,
+                        fail_count,
+                    },
+                );
+            }
+        }
+
+        DataStore {
+            path: path.to_string(),
+            entries,
+        }
+    }
+
+    // Rewrites the whole store to disk, one line per entry.
+    pub fn save(&self) -> io::Result<()> {
+        let file = File::create(&self.path)?;
+        let mut out = BufWriter::new(file);
+        for ((ip, port), entry) in &self.entries {
+            writeln!(
+                out,
+                "{},{},{},{},{},{},{}",
+                ip,
+                port,
+                entry.state.as_str(),
+                entry.avg_latency_ms,
+                entry.last_seen_unix,
+                entry.success_count,
+                entry.fail_count
+            )?;
+        }
+        Ok(())
+    }
+
+    // Records a successful probe, folding the new sample into a rolling
+    // average so a single lucky (or unlucky) latency spike can't yank a
+    // node in or out of the top results.
+    pub fn record_success(&mut self, ip: &str, port: u16, latency_ms: f64, now_unix: u64) {
+        let entry = self
+            .entries
+            .entry((ip.to_string(), port))
+            .or_insert_with(AddressEntry::new);
+
+        entry.avg_latency_ms = if entry.success_count == 0 {
+            latency_ms
+        } else {
+            // Exponential moving average: weight recent samples more than
+            // distant history without keeping every sample around.
+            entry.avg_latency_ms * 0.7 + latency_ms * 0.3
+        };
+        entry.success_count += 1;
+        entry.last_seen_unix = now_unix;
+        entry.state = AddressState::Good;
+    }
+
+    // Records a failed probe under the given terminal state (Timeout,
+    // Refused, or EvilNode). An entry that was Good is demoted to WasGood
+    // instead.
+    pub fn record_failure(&mut self, ip: &str, port: u16, state: AddressState, now_unix: u64) {
+        let entry = self
+            .entries
+            .entry((ip.to_string(), port))
+            .or_insert_with(AddressEntry::new);
+
+        if entry.state == AddressState::Good {
+            entry.state = AddressState::WasGood;
+        } else {
+            entry.state = state;
+        }
+        entry.fail_count += 1;
+        entry.last_seen_unix = now_unix;
+    }
+
+    // Whether `ip:port` should be skipped this run: it's currently in a
+    // known-bad state and hasn't gone stale enough to deserve a retest.
+    pub fn should_skip(&self, ip: &str, port: u16, retest_after_secs: u64, now_unix: u64) -> bool {
+        match self.entries.get(&(ip.to_string(), port)) {
+            Some(entry) => match entry.state {
+                AddressState::Timeout | AddressState::Refused | AddressState::EvilNode => {
+                    now_unix.saturating_sub(entry.last_seen_unix) < retest_after_secs
+                }
+                _ => false,
+            },
+            None => false,
+        }
+    }
+}
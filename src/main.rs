@@ -1,14 +1,42 @@
 use std::env;
+use std::fs;
 use std::fs::File;
 use std::io::{self, BufRead, BufWriter, Write};
 use std::net::{IpAddr, TcpStream, ToSocketAddrs};
 use std::process;
 use std::sync::mpsc::{channel};
+use std::sync::{Arc, Condvar, Mutex};
 use std::thread;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use url::{Url};
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+
+mod asn;
+mod config;
+mod datastore;
+mod server;
+mod tls_probe;
+use asn::AsnDb;
+use config::OptimizerConfig;
+use datastore::{AddressState, DataStore};
+use std::net::SocketAddr;
+
+// Default path a `--wizard` run saves its config to when no `--config PATH`
+// is given alongside it.
+const DEFAULT_CONFIG_PATH: &str = "optimizer.yaml";
+
+// Number of scan workers used when --workers is not given and the number of
+// available cores cannot be determined.
+const DEFAULT_WORKERS: usize = 4;
+// Default global cap on new connection attempts per second.
+const DEFAULT_MAX_CPS: u32 = 50;
+// Default path for the persistent per-IP result store.
+const DEFAULT_STORE_PATH: &str = "cdn_optimizer_store.txt";
+// Default age (in seconds) before a Timeout, Refused or EvilNode entry gets
+// probed again. Good and WasGood entries are probed on every run regardless
+// of age.
+const DEFAULT_RETEST_AFTER_SECS: u64 = 6 * 3600;
 
 // NodeConfig represents a parsed node configuration
 #[derive(Clone)]
@@ -21,11 +49,28 @@ struct NodeConfig {
     fragment: String, // Added to store the fragment (node name)
 }
 
-// Result represents a tested IP with its latency
+// Result represents a tested IP with its (rolling average) latency and,
+// if an ASN database was supplied, the network it belongs to.
 #[derive(Clone)]
 struct Result {
     ip: String,
     latency: Duration,
+    asn: Option<(u32, String)>,
+}
+
+// ProbeOutcome is what a worker reports back for a single IP, whether the
+// connection succeeded or failed, so the caller can update the datastore.
+enum ProbeOutcome {
+    Success { ip: String, latency: Duration },
+    Failure { ip: String, state: AddressState },
+}
+
+// now_unix returns the current time as a Unix timestamp in seconds.
+fn now_unix() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
 }
 
 // parse_node_link parses a node link (e.g., vless://uuid@address:port?params#fragment)
@@ -99,13 +144,308 @@ fn generate_node_link(config: &NodeConfig, new_ip: &str) -> String {
     u
 }
 
+// How often the bucket wakes up to top itself off. The amount it adds each
+// tick is derived from the real elapsed time (see TokenBucket::new) rather
+// than a fixed per-tick share of max_cps, so the result is accurate for any
+// max_cps — including ones smaller than the tick rate itself.
+const REFILL_INTERVAL: Duration = Duration::from_millis(100);
+
+// Tokens plus the instant they were last topped off, so each tick can grant
+// exactly `elapsed * max_cps` permits instead of a fixed, pre-divided share.
+struct TokenBucketState {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+// TokenBucket caps the number of connection attempts that may start per
+// second across all workers, so a large IP list doesn't hammer the CDN
+// edge hard enough to trip its rate limiting.
+struct TokenBucket {
+    state: Mutex<TokenBucketState>,
+    cond: Condvar,
+    max_cps: u32,
+}
+
+impl TokenBucket {
+    fn new(max_cps: u32) -> Arc<TokenBucket> {
+        let bucket = Arc::new(TokenBucket {
+            state: Mutex::new(TokenBucketState {
+                tokens: max_cps as f64,
+                last_refill: Instant::now(),
+            }),
+            cond: Condvar::new(),
+            max_cps,
+        });
+
+        // Background ticker: every REFILL_INTERVAL, grant exactly as many
+        // tokens as max_cps permits per second of real elapsed time (capped
+        // at max_cps total), then wake anyone waiting on a permit. Deriving
+        // the grant from elapsed time rather than a fixed `max_cps /
+        // ticks_per_sec` share keeps the rate accurate even when max_cps is
+        // smaller than the number of ticks per second.
+        let refill = Arc::clone(&bucket);
+        thread::spawn(move || loop {
+            thread::sleep(REFILL_INTERVAL);
+            let mut state = refill.state.lock().unwrap();
+            let now = Instant::now();
+            let elapsed = now.duration_since(state.last_refill).as_secs_f64();
+            state.tokens = (state.tokens + elapsed * refill.max_cps as f64).min(refill.max_cps as f64);
+            state.last_refill = now;
+            refill.cond.notify_all();
+        });
+
+        bucket
+    }
+
+    // Blocks until a permit is available, then consumes it.
+    fn acquire(&self) {
+        let mut state = self.state.lock().unwrap();
+        while state.tokens < 1.0 {
+            state = self.cond.wait(state).unwrap();
+        }
+        state.tokens -= 1.0;
+    }
+}
+
+// Parses an optional `--flag value` pair out of the positional args,
+// returning the value and removing the flag and its value from `args`.
+fn take_flag(args: &mut Vec<String>, flag: &str) -> Option<String> {
+    if let Some(pos) = args.iter().position(|a| a == flag) {
+        if pos + 1 < args.len() {
+            args.remove(pos); // flag name
+            return Some(args.remove(pos)); // value
+        }
+    }
+    None
+}
+
+// Parses a valueless boolean `--flag`, returning whether it was present.
+fn take_bool_flag(args: &mut Vec<String>, flag: &str) -> bool {
+    if let Some(pos) = args.iter().position(|a| a == flag) {
+        args.remove(pos);
+        true
+    } else {
+        false
+    }
+}
+
+// RankedLink is one scored result for a single node in a subscription,
+// produced by the `--config`-driven scan path.
+struct RankedLink {
+    link: String,
+    ip: String,
+    latency: Duration,
+}
+
+// scan_simple runs a plain bounded-concurrency scan of `ips` against one
+// node and returns the fastest `top_n`, sorted. It's the shared core used
+// by the `--config`/`--wizard` path, which (unlike the default CLI path)
+// has no per-run datastore, ASN annotation, or TLS mode to thread through.
+fn scan_simple(config: &NodeConfig, ips: &[String], workers: usize, timeout: Duration, top_n: usize) -> Vec<RankedLink> {
+    let queue = Arc::new(Mutex::new(VecDeque::from(ips.to_vec())));
+    let (tx, rx) = channel();
+    let mut handles = Vec::with_capacity(workers);
+
+    for _ in 0..workers {
+        let queue = Arc::clone(&queue);
+        let tx = tx.clone();
+        let config = config.clone();
+        handles.push(thread::spawn(move || loop {
+            let ip = match queue.lock().unwrap().pop_front() {
+                Some(ip) => ip,
+                None => break,
+            };
+            if let Ok(latency) = test_ip_latency(&ip, config.port, timeout) {
+                tx.send((ip, latency)).unwrap();
+            }
+        }));
+    }
+    drop(tx);
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    let mut results: Vec<(String, Duration)> = rx.iter().collect();
+    results.sort_by_key(|(_, latency)| *latency);
+
+    results
+        .into_iter()
+        .take(top_n)
+        .map(|(ip, latency)| RankedLink {
+            link: generate_node_link(config, &ip),
+            ip,
+            latency,
+        })
+        .collect()
+}
+
+// run_from_config drives the whole parse -> scan -> sort -> output
+// pipeline from a saved OptimizerConfig instead of positional CLI args,
+// scanning the same IP list against every node link in the subscription.
+fn run_from_config(path: &str) {
+    let cfg = match OptimizerConfig::load(path) {
+        Ok(c) => c,
+        Err(e) => {
+            println!("Error loading config {}: {}", path, e);
+            process::exit(1);
+        }
+    };
+
+    let file = match File::open(&cfg.ip_list_file) {
+        Ok(f) => f,
+        Err(e) => {
+            println!("Error opening IP list file: {}", e);
+            process::exit(1);
+        }
+    };
+    let mut ips: Vec<String> = Vec::new();
+    for line in io::BufReader::new(file).lines() {
+        let ip_str = line.unwrap().trim().to_string();
+        if ip_str.parse::<IpAddr>().is_ok() {
+            ips.push(ip_str);
+        }
+    }
+
+    let timeout = Duration::from_millis(cfg.timeout_ms);
+    let mut per_node: Vec<(String, Vec<RankedLink>)> = Vec::new();
+    for node_link in &cfg.node_links {
+        let node_config = match parse_node_link(node_link) {
+            Ok(c) => c,
+            Err(e) => {
+                println!("Error parsing node link {}: {}", node_link, e);
+                continue;
+            }
+        };
+        let ranked = scan_simple(&node_config, &ips, cfg.workers, timeout, cfg.top_n);
+        per_node.push((node_link.clone(), ranked));
+    }
+
+    match cfg.output.format.as_str() {
+        "json" => write_config_output_json(&per_node),
+        "base64" => write_config_output_base64(&per_node),
+        _ => write_config_output_text(&per_node),
+    }
+}
+
+fn write_config_output_text(per_node: &[(String, Vec<RankedLink>)]) {
+    let output_file = File::create("optimized_subscription.txt").expect("Error creating output file");
+    let mut output = BufWriter::new(output_file);
+    for (node_link, ranked) in per_node {
+        let line = format!("# {}\n", node_link);
+        output.write_all(line.as_bytes()).unwrap();
+        for r in ranked {
+            let line = format!("{} (Latency: {:?})\n", r.link, r.latency);
+            output.write_all(line.as_bytes()).unwrap();
+            print!("{}", line);
+        }
+    }
+    println!("Generated optimized_subscription.txt");
+}
+
+fn write_config_output_json(per_node: &[(String, Vec<RankedLink>)]) {
+    let nodes: Vec<serde_json::Value> = per_node
+        .iter()
+        .map(|(node_link, ranked)| {
+            let results: Vec<serde_json::Value> = ranked
+                .iter()
+                .map(|r| {
+                    serde_json::json!({
+                        "link": r.link,
+                        "ip": r.ip,
+                        // as_millis() truncates sub-millisecond handshakes to
+                        // 0; keep fractional precision so ordering stays
+                        // legible in the JSON output.
+                        "latency_ms": r.latency.as_secs_f64() * 1000.0,
+                    })
+                })
+                .collect();
+            serde_json::json!({ "node_link": node_link, "results": results })
+        })
+        .collect();
+
+    let body = serde_json::to_string_pretty(&nodes).unwrap();
+    fs::write("optimized_subscription.json", &body).expect("Error creating output file");
+    println!("Generated optimized_subscription.json");
+}
+
+fn write_config_output_base64(per_node: &[(String, Vec<RankedLink>)]) {
+    use base64::Engine as _;
+
+    let links: Vec<&str> = per_node
+        .iter()
+        .flat_map(|(_, ranked)| ranked.iter().map(|r| r.link.as_str()))
+        .collect();
+    let blob = base64::engine::general_purpose::STANDARD.encode(links.join("\n"));
+    fs::write("optimized_subscription.txt", &blob).expect("Error creating output file");
+    println!("Generated base64 subscription blob in optimized_subscription.txt");
+}
+
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
+
+    // `serve` runs the optimizer as a long-lived HTTP JSON API instead of
+    // the one-shot file-based CLI; everything below stays unchanged.
+    if args.len() > 1 && args[1] == "serve" {
+        let addr_str = take_flag(&mut args, "--addr").unwrap_or_else(|| "127.0.0.1:8080".to_string());
+        let addr: SocketAddr = addr_str.parse().expect("Invalid --addr");
+        server::run(addr);
+        return;
+    }
+
+    // `--wizard` interactively builds a config file and saves it for reuse;
+    // `--config` drives the whole pipeline from a previously saved one.
+    // Both replace the brittle positional-arg CLI path below.
+    if take_bool_flag(&mut args, "--wizard") {
+        let config_path = take_flag(&mut args, "--config").unwrap_or_else(|| DEFAULT_CONFIG_PATH.to_string());
+        let cfg = config::run_wizard();
+        if let Err(e) = cfg.save(&config_path) {
+            println!("Error saving config: {}", e);
+            process::exit(1);
+        }
+        println!("Saved config to {}. Run again with --config {} to use it.", config_path, config_path);
+        return;
+    }
+    if let Some(config_path) = take_flag(&mut args, "--config") {
+        run_from_config(&config_path);
+        return;
+    }
+
+    let workers: usize = take_flag(&mut args, "--workers")
+        .map(|v| v.parse().expect("Invalid --workers"))
+        .unwrap_or_else(|| {
+            thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(DEFAULT_WORKERS)
+        });
+    let max_cps: u32 = take_flag(&mut args, "--max-cps")
+        .map(|v| v.parse().expect("Invalid --max-cps"))
+        .unwrap_or(DEFAULT_MAX_CPS);
+    let store_path = take_flag(&mut args, "--store").unwrap_or_else(|| DEFAULT_STORE_PATH.to_string());
+    let retest_after: u64 = take_flag(&mut args, "--retest-after")
+        .map(|v| v.parse().expect("Invalid --retest-after"))
+        .unwrap_or(DEFAULT_RETEST_AFTER_SECS);
+    let mode = take_flag(&mut args, "--mode").unwrap_or_else(|| "tcp".to_string());
+    let asn_db_path = take_flag(&mut args, "--asn-db");
+    let max_per_asn: usize = take_flag(&mut args, "--max-per-asn")
+        .map(|v| v.parse().expect("Invalid --max-per-asn"))
+        .unwrap_or(0); // 0 means unlimited
+
     if args.len() < 3 {
-        println!("Usage: cdn_optimizer <node_link> <ip_list_file> [timeout_ms]");
+        println!("Usage: cdn_optimizer <node_link> <ip_list_file> [timeout_ms] [--workers N] [--max-cps N] [--store PATH] [--retest-after SECS] [--mode tcp|tls] [--asn-db PATH] [--max-per-asn N]");
+        println!("       cdn_optimizer serve [--addr HOST:PORT]");
+        println!("       cdn_optimizer --wizard [--config PATH]");
+        println!("       cdn_optimizer --config PATH");
         process::exit(1);
     }
 
+    let asn_db = asn_db_path.map(|path| match AsnDb::load(&path) {
+        Ok(db) => db,
+        Err(e) => {
+            println!("Error loading ASN database: {}", e);
+            process::exit(1);
+        }
+    });
+
     let node_link = &args[1];
     let ip_list_file = &args[2];
     let mut timeout = Duration::from_secs(3);
@@ -133,34 +473,121 @@ fn main() {
     };
     let reader = io::BufReader::new(file);
 
+    // TLS mode only actually performs a handshake when the node itself is
+    // configured for it; otherwise a plain TCP connect is all there is to
+    // check, so we silently fall back rather than erroring out.
+    let security = config.params.get("security").map(|s| s.as_str()).unwrap_or("");
+    let use_tls = mode == "tls" && (security == "tls" || security == "reality");
+    let sni = config
+        .params
+        .get("sni")
+        .or_else(|| config.params.get("host"))
+        .cloned()
+        .unwrap_or_else(|| config.address.clone());
+    let alpn = config
+        .params
+        .get("alpn")
+        .and_then(|v| v.split(',').next())
+        .map(|s| s.to_string());
+
+    let mut store = DataStore::load(&store_path);
+    let now = now_unix();
+
     let mut ips: Vec<String> = Vec::new();
     for line in reader.lines() {
         let ip_str = line.unwrap().trim().to_string();
-        if ip_str.parse::<IpAddr>().is_ok() {
+        if ip_str.parse::<IpAddr>().is_ok() && !store.should_skip(&ip_str, config.port, retest_after, now) {
             ips.push(ip_str);
         }
     }
 
-    // Test IPs concurrently
+    // The store is keyed only by (ip, port), so a Good entry for this port
+    // may have been proven good against a different node link on the same
+    // port (or an IP not even in today's list). Remember which IPs this run
+    // actually probed so the output below can be scoped to them instead of
+    // every Good (ip, port) in the whole store.
+    let probed_ips: std::collections::HashSet<String> = ips.iter().cloned().collect();
+
+    // Test IPs using a bounded worker pool, rate-limited by a token bucket,
+    // instead of spawning one OS thread per IP (which exhausts file
+    // descriptors on large CIDR dumps).
+    let queue = Arc::new(Mutex::new(VecDeque::from(ips)));
+    let bucket = TokenBucket::new(max_cps);
     let (tx, rx) = channel();
-    for ip in ips {
+    let mut handles = Vec::with_capacity(workers);
+
+    for _ in 0..workers {
+        let queue = Arc::clone(&queue);
+        let bucket = Arc::clone(&bucket);
         let tx = tx.clone();
         let config = config.clone();
-        thread::spawn(move || {
-            if let Ok(latency) = test_ip_latency(&ip, config.port, timeout) {
-                tx.send(Result { ip, latency }).unwrap();
+        let sni = sni.clone();
+        let alpn = alpn.clone();
+        handles.push(thread::spawn(move || loop {
+            let ip = match queue.lock().unwrap().pop_front() {
+                Some(ip) => ip,
+                None => break,
+            };
+            bucket.acquire();
+            let outcome = if use_tls {
+                tls_probe::test_tls_handshake(&ip, config.port, &sni, alpn.as_deref(), timeout)
+                    .map(|r| r.latency)
+            } else {
+                test_ip_latency(&ip, config.port, timeout)
+            };
+            match outcome {
+                Ok(latency) => tx.send(ProbeOutcome::Success { ip, latency }).unwrap(),
+                Err(e) => {
+                    let state = match e.kind() {
+                        io::ErrorKind::TimedOut => AddressState::Timeout,
+                        io::ErrorKind::ConnectionRefused => AddressState::Refused,
+                        _ => AddressState::Timeout,
+                    };
+                    tx.send(ProbeOutcome::Failure { ip, state }).unwrap();
+                }
             }
-        });
+        }));
+    }
+
+    drop(tx); // Close the channel once every worker holds its own clone
+    for handle in handles {
+        handle.join().unwrap();
     }
 
-    drop(tx); // Close the channel after spawning all threads
+    // Fold this run's outcomes into the persistent store, then rank by the
+    // store's rolling average latency so a single lucky/unlucky sample
+    // can't flip the ordering.
+    for outcome in rx.iter() {
+        match outcome {
+            ProbeOutcome::Success { ip, latency } => {
+                store.record_success(&ip, config.port, latency.as_secs_f64() * 1000.0, now);
+            }
+            ProbeOutcome::Failure { ip, state } => {
+                store.record_failure(&ip, config.port, state, now);
+            }
+        }
+    }
 
-    // Collect results
-    let mut valid_results: Vec<Result> = rx.iter().collect();
+    let mut valid_results: Vec<Result> = store
+        .entries
+        .iter()
+        .filter(|((ip, port), entry)| {
+            *port == config.port && entry.state == AddressState::Good && probed_ips.contains(ip)
+        })
+        .map(|((ip, _), entry)| Result {
+            ip: ip.clone(),
+            latency: Duration::from_secs_f64(entry.avg_latency_ms / 1000.0),
+            asn: asn_db.as_ref().and_then(|db| db.lookup(ip)),
+        })
+        .collect();
 
     // Sort results by latency
     valid_results.sort_by_key(|r| r.latency);
 
+    if let Err(e) = store.save() {
+        println!("Warning: failed to save datastore: {}", e);
+    }
+
     // Generate output file
     let output_file = match File::create("optimized_nodes.txt") {
         Ok(f) => f,
@@ -171,13 +598,39 @@ fn main() {
     };
     let mut output = BufWriter::new(output_file);
 
-    // Write optimized node links (top 10 or all if fewer)
-    let count = std::cmp::min(10, valid_results.len());
-    for i in 0..count {
-        let new_link = generate_node_link(&config, &valid_results[i].ip);
-        let line = format!("{} (Latency: {:?})\n", new_link, valid_results[i].latency);
+    // Write optimized node links (top 10 or all if fewer), spreading across
+    // distinct ASNs when --max-per-asn caps how many IPs from the same
+    // network may appear in the final list.
+    let mut asn_counts: HashMap<u32, usize> = HashMap::new();
+    let mut count = 0;
+    for result in &valid_results {
+        if count >= 10 {
+            break;
+        }
+        if max_per_asn > 0 {
+            // AS0 is reserved for "unknown/invalid origin" (RFC 7607), so
+            // it doubles here as the bucket for IPs the ASN db couldn't
+            // place (unmatched, or IPv6 — AsnDb::lookup is IPv4-only).
+            // Without this, an all-IPv6 or unmatched list would bypass
+            // --max-per-asn entirely.
+            let asn = result.asn.as_ref().map(|(asn, _)| *asn).unwrap_or(0);
+            let used = asn_counts.entry(asn).or_insert(0);
+            if *used >= max_per_asn {
+                continue;
+            }
+            *used += 1;
+        }
+
+        let new_link = generate_node_link(&config, &result.ip);
+        let asn_suffix = match &result.asn {
+            Some((asn, name)) if !name.is_empty() => format!(" [AS{} {}]", asn, name),
+            Some((asn, _)) => format!(" [AS{}]", asn),
+            None => String::new(),
+        };
+        let line = format!("{} (Latency: {:?}){}\n", new_link, result.latency, asn_suffix);
         output.write_all(line.as_bytes()).unwrap();
         print!("{}", line);
+        count += 1;
     }
 
     println!("Generated {} optimized node links in optimized_nodes.txt", count);
@@ -0,0 +1,209 @@
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::mpsc::channel;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use std::collections::VecDeque;
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::{generate_node_link, parse_node_link, test_ip_latency, NodeConfig, TokenBucket};
+
+// Caps on an untrusted POST /optimize body: without these a client can
+// hand us an arbitrarily large `ips` array (or body) and force unbounded
+// thread spawns / memory use.
+const MAX_BODY_BYTES: u64 = 1024 * 1024; // 1 MiB
+const MAX_IPS_PER_REQUEST: usize = 2048;
+const SERVER_WORKERS: usize = 8;
+const SERVER_MAX_CPS: u32 = 200;
+
+#[derive(Deserialize)]
+struct OptimizeRequest {
+    node_link: String,
+    ips: Vec<String>,
+    #[serde(default = "default_timeout_ms")]
+    timeout_ms: u64,
+    #[serde(default = "default_top_n")]
+    top_n: usize,
+}
+
+fn default_timeout_ms() -> u64 {
+    3000
+}
+
+fn default_top_n() -> usize {
+    10
+}
+
+#[derive(Serialize)]
+struct RankedNode {
+    link: String,
+    ip: String,
+    latency_ms: f64,
+}
+
+// run starts the optimizer as a long-lived HTTP JSON API on `addr`, for
+// embedding in panels/subscription generators that want to refresh CDN
+// fronts without shelling out to the CLI and parsing optimized_nodes.txt.
+pub fn run(addr: SocketAddr) {
+    let rt = tokio::runtime::Runtime::new().expect("Failed to start Tokio runtime");
+    rt.block_on(serve(addr));
+}
+
+async fn serve(addr: SocketAddr) {
+    // One shared rate limiter for the whole server's lifetime, not one per
+    // request — TokenBucket::new spawns a refill ticker thread, so handing
+    // out a fresh one per request would leak a thread per scan.
+    let bucket = TokenBucket::new(SERVER_MAX_CPS);
+
+    let make_svc = make_service_fn(move |_conn| {
+        let bucket = Arc::clone(&bucket);
+        async move { Ok::<_, Infallible>(service_fn(move |req| handle(req, Arc::clone(&bucket)))) }
+    });
+
+    let server = Server::bind(&addr).serve(make_svc);
+    println!("Listening on http://{}", addr);
+    if let Err(e) = server.await {
+        eprintln!("Server error: {}", e);
+    }
+}
+
+async fn handle(
+    req: Request<Body>,
+    bucket: Arc<TokenBucket>,
+) -> std::result::Result<Response<Body>, Infallible> {
+    if req.method() != Method::POST || req.uri().path() != "/optimize" {
+        return Ok(not_found());
+    }
+
+    let declared_len = req
+        .headers()
+        .get(hyper::header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+    if declared_len.is_none_or(|len| len > MAX_BODY_BYTES) {
+        return Ok(payload_too_large());
+    }
+
+    let body_bytes = match hyper::body::to_bytes(req.into_body()).await {
+        Ok(b) => b,
+        Err(_) => return Ok(bad_request("failed to read request body")),
+    };
+    if body_bytes.len() as u64 > MAX_BODY_BYTES {
+        return Ok(payload_too_large());
+    }
+
+    let mut payload: OptimizeRequest = match serde_json::from_slice(&body_bytes) {
+        Ok(p) => p,
+        Err(e) => return Ok(bad_request(&format!("invalid JSON: {}", e))),
+    };
+
+    if payload.ips.len() > MAX_IPS_PER_REQUEST {
+        return Ok(bad_request(&format!(
+            "ips list too long: max {} entries",
+            MAX_IPS_PER_REQUEST
+        )));
+    }
+
+    // Drop anything that isn't a literal IP address, matching the CLI path
+    // (src/main.rs's `ip_str.parse::<IpAddr>().is_ok()` filter). Without
+    // this, a hostname like "localhost" or an internal DNS name flows
+    // straight into test_ip_latency's to_socket_addrs() resolve-and-connect,
+    // turning this endpoint into an open internal port scanner.
+    payload.ips.retain(|ip| ip.parse::<IpAddr>().is_ok());
+
+    let config = match parse_node_link(&payload.node_link) {
+        Ok(c) => c,
+        Err(e) => return Ok(bad_request(&format!("invalid node_link: {}", e))),
+    };
+
+    let timeout = Duration::from_millis(payload.timeout_ms);
+    payload.top_n = payload.top_n.min(MAX_IPS_PER_REQUEST);
+    let ranked = optimize(&config, payload.ips, timeout, payload.top_n, bucket);
+
+    let body = serde_json::to_vec(&ranked).unwrap_or_default();
+    Ok(Response::builder()
+        .header("content-type", "application/json")
+        .body(Body::from(body))
+        .unwrap())
+}
+
+fn not_found() -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::NOT_FOUND)
+        .body(Body::from("not found"))
+        .unwrap()
+}
+
+fn bad_request(msg: &str) -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::BAD_REQUEST)
+        .body(Body::from(msg.to_string()))
+        .unwrap()
+}
+
+fn payload_too_large() -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::PAYLOAD_TOO_LARGE)
+        .body(Body::from(format!(
+            "request body/ips list too large (max {} bytes, {} ips)",
+            MAX_BODY_BYTES, MAX_IPS_PER_REQUEST
+        )))
+        .unwrap()
+}
+
+// optimize runs one parse→scan→sort pass for a single API request, using
+// the same bounded worker pool + rate limiter as the CLI path (chunk0-1)
+// instead of spawning one OS thread per IP — the ips list here comes from
+// an untrusted request body, so nothing bounds it on its own.
+fn optimize(
+    config: &NodeConfig,
+    ips: Vec<String>,
+    timeout: Duration,
+    top_n: usize,
+    bucket: Arc<TokenBucket>,
+) -> Vec<RankedNode> {
+    let queue = Arc::new(Mutex::new(VecDeque::from(ips)));
+    let (tx, rx) = channel();
+    let mut handles = Vec::with_capacity(SERVER_WORKERS);
+
+    for _ in 0..SERVER_WORKERS {
+        let queue = Arc::clone(&queue);
+        let bucket = Arc::clone(&bucket);
+        let tx = tx.clone();
+        let port = config.port;
+        handles.push(thread::spawn(move || loop {
+            let ip = match queue.lock().unwrap().pop_front() {
+                Some(ip) => ip,
+                None => break,
+            };
+            bucket.acquire();
+            if let Ok(latency) = test_ip_latency(&ip, port, timeout) {
+                tx.send((ip, latency)).unwrap();
+            }
+        }));
+    }
+    drop(tx);
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    let mut results: Vec<(String, Duration)> = rx.iter().collect();
+    results.sort_by_key(|(_, latency)| *latency);
+
+    results
+        .into_iter()
+        .take(top_n)
+        .map(|(ip, latency)| RankedNode {
+            link: generate_node_link(config, &ip),
+            ip,
+            // Sub-millisecond handshakes would all serialize as 0 with
+            // as_millis(); keep fractional precision so ordering stays
+            // legible in the JSON response.
+            latency_ms: latency.as_secs_f64() * 1000.0,
+        })
+        .collect()
+}
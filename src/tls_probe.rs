@@ -0,0 +1,83 @@
+use std::io;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use rustls::{ClientConfig, ClientConnection, OwnedTrustAnchor, RootCertStore, ServerName};
+
+// TlsProbeResult is what a completed TLS health check reports back.
+pub struct TlsProbeResult {
+    pub latency: Duration,
+}
+
+fn root_store() -> RootCertStore {
+    let mut store = RootCertStore::empty();
+    store.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|ta| {
+        OwnedTrustAnchor::from_subject_spki_name_constraints(
+            ta.subject,
+            ta.spki,
+            ta.name_constraints,
+        )
+    }));
+    store
+}
+
+// test_tls_handshake connects to ip:port and performs a real ClientHello
+// with `sni` as the Server Name Indication, the way a vless/trojan client
+// would. A bare TCP connect says nothing about whether the IP actually
+// terminates the right CDN edge; completing the handshake does, because
+// rustls rejects the connection outright if the presented certificate is
+// expired or doesn't cover `sni`. `alpn` is checked against the
+// negotiated protocol when given (e.g. "h2", "http/1.1").
+pub fn test_tls_handshake(
+    ip: &str,
+    port: u16,
+    sni: &str,
+    alpn: Option<&str>,
+    timeout: Duration,
+) -> io::Result<TlsProbeResult> {
+    let addr = format!("{}:{}", ip, port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Invalid address"))?;
+
+    let mut config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(root_store())
+        .with_no_client_auth();
+    if let Some(proto) = alpn {
+        config.alpn_protocols = vec![proto.as_bytes().to_vec()];
+    }
+
+    let server_name = ServerName::try_from(sni)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Invalid SNI"))?;
+    let mut conn =
+        ClientConnection::new(Arc::new(config), server_name).map_err(io::Error::other)?;
+
+    let start = Instant::now();
+    let mut sock = TcpStream::connect_timeout(&addr, timeout)?;
+    sock.set_read_timeout(Some(timeout))?;
+    sock.set_write_timeout(Some(timeout))?;
+
+    // complete_io drives rustls through the full ClientHello/ServerHello
+    // exchange over the raw socket; nothing short of this actually sends
+    // a handshake, so the certificate (and its expiry) genuinely gets
+    // validated before we call this a success.
+    while conn.is_handshaking() {
+        conn.complete_io(&mut sock)?;
+    }
+    let latency = start.elapsed();
+
+    if conn
+        .peer_certificates()
+        .is_none_or(|certs| certs.is_empty())
+    {
+        return Err(io::Error::other("no certificate presented during handshake"));
+    }
+
+    if alpn.is_some() && conn.alpn_protocol().is_none() {
+        return Err(io::Error::other("ALPN negotiation failed"));
+    }
+
+    Ok(TlsProbeResult { latency })
+}